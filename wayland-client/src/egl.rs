@@ -5,13 +5,25 @@
 //! This library is used to interface with the OpenGL stack, and creating
 //! EGL surfaces from a wayland surface.
 //!
-//! See WlEglSurface documentation for details.
+//! See WlEglSurface documentation for details. For a full EGL display and
+//! context built on top of it (so you don't have to hand-roll the usual
+//! `eglGetPlatformDisplayEXT`/`eglInitialize`/`eglCreateContext` dance
+//! yourself), see `WlEglContext`.
 
-use std::os::raw::c_void;
+use std::error::Error;
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_char, c_void};
 use std::ops::Deref;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use wayland_sys::client::wl_proxy;
 use wayland_sys::egl::*;
-use sys::wayland::client::WlSurface;
+use sys::wayland::client::{WlDisplay, WlSurface};
 use Proxy;
 
 /// Checks if the wayland-egl lib is available and can be used
@@ -38,9 +50,17 @@ unsafe impl Sync for WlEglSurface {}
 ///
 /// If you let it go out of scope, it'll destroy the underlying `WlSurface`. If you
 /// need to get it back, use the `destroy` method.
+///
+/// If you hand this surface to `WlEglContext::create_window_surface` (which
+/// requires wrapping it in an `Rc` first), the `WlEglSurface` keeps track of
+/// the `EGLSurface`s created from it and refuses to be destroyed while any
+/// of them are still alive, since Mesa's `wl_egl_window` is not safe to free
+/// while an `EGLSurface` derived from it is still current (see `try_destroy`).
 pub struct WlEglSurface {
     ptr: *mut wl_egl_window,
-    surface: WlSurface
+    surface: WlSurface,
+    live_egl_surfaces: AtomicUsize,
+    pending_resize: Arc<Mutex<Option<PendingResize>>>,
 }
 
 impl WlEglSurface {
@@ -50,20 +70,83 @@ impl WlEglSurface {
             surface.ptr(), width, height) };
         WlEglSurface {
             ptr: ptr,
-            surface: surface
+            surface: surface,
+            live_egl_surfaces: AtomicUsize::new(0),
+            pending_resize: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Create an EGL surface from a raw `wl_surface` pointer
+    ///
+    /// This is useful when the `wl_surface` was not obtained through this
+    /// crate, for instance when it comes from a toolkit such as `winit` or
+    /// from a C library linked into the process.
+    ///
+    /// # Safety
+    ///
+    /// `surface_ptr` must be a valid pointer to a live `wl_surface` proxy
+    /// created by `libwayland-client`, and it must remain valid (i.e. not
+    /// destroyed, and its connection to the display not closed) for as long
+    /// as the returned `WlEglSurface` is alive. This constructor does not
+    /// take ownership of the surface: dropping the `WlEglSurface` will
+    /// destroy the underlying `wl_egl_window`, but not the `wl_surface`
+    /// itself, which remains the caller's responsibility.
+    pub unsafe fn from_raw(surface_ptr: *mut wl_proxy, width: i32, height: i32) -> WlEglSurface {
+        WlEglSurface::new(WlSurface::from_c_ptr(surface_ptr), width, height)
+    }
+
+    /// Destroy the EGL surface, giving back the original wayland surface
+    ///
+    /// # Panics
+    ///
+    /// Panics if any `EGLSurface` created from this `WlEglSurface` (via
+    /// `WlEglContext::create_window_surface`) is still alive. Use
+    /// `try_destroy` to handle this case without panicking.
+    pub fn destroy(self) -> WlSurface {
+        match self.try_destroy() {
+            Ok(surface) => surface,
+            Err((_, err)) => panic!("{}", err),
         }
     }
 
     /// Destroy the EGL surface, giving back the original wayland surface
-    pub fn destroy(mut self) -> WlSurface {
+    ///
+    /// Unlike `destroy`, this does not panic: it fails and hands the
+    /// `WlEglSurface` back to the caller if any `EGLSurface` created from it
+    /// is still alive, since destroying the underlying `wl_egl_window` in
+    /// that situation is a use-after-free for whoever holds that `EGLSurface`.
+    pub fn try_destroy(mut self) -> Result<WlSurface, (WlEglSurface, EglError)> {
+        if self.live_egl_surfaces.load(Ordering::Acquire) > 0 {
+            return Err((self, EglError::SurfaceStillInUse));
+        }
         unsafe { ffi_dispatch!(WAYLAND_EGL_HANDLE, wl_egl_window_destroy, self.ptr); }
         let surface = ::std::mem::replace(&mut self.surface, unsafe { ::std::mem::uninitialized() });
+        // `self` is about to be `mem::forget`'d (to stop its `Drop` impl from
+        // re-running `wl_egl_window_destroy`), which would otherwise leak the
+        // `Arc`'s strong count (and pin its allocation if a `ResizeHandle`
+        // clone is still outstanding): take it out and drop it for real.
+        let pending_resize = ::std::mem::replace(&mut self.pending_resize, unsafe { ::std::mem::uninitialized() });
         ::std::mem::forget(self);
-        surface
+        drop(pending_resize);
+        Ok(surface)
+    }
+
+    fn inc_live_surfaces(&self) {
+        self.live_egl_surfaces.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn dec_live_surfaces(&self) {
+        self.live_egl_surfaces.fetch_sub(1, Ordering::AcqRel);
     }
 
     /// Fetch current size of the EGL surface
+    ///
+    /// Applies any resize queued through a `ResizeHandle` first. The size
+    /// returned is the attached size of the `wl_egl_window` (that of the last
+    /// buffer attached to it), so a resize applied here only shows up in it
+    /// after the next buffer swap.
     pub fn get_size(&self) -> (i32, i32) {
+        self.apply_pending_resize();
         let mut w = 0i32;
         let mut h = 0i32;
         unsafe { ffi_dispatch!(WAYLAND_EGL_HANDLE, wl_egl_window_get_attached_size,
@@ -82,6 +165,26 @@ impl WlEglSurface {
             self.ptr, width, height, dx, dy) }
     }
 
+    /// Get a cheap, `Clone + Send` handle to queue resizes on this surface
+    ///
+    /// This is meant to be handed to an event-loop thread that receives
+    /// compositor `configure` events, so it can feed new geometry to the
+    /// render thread without needing a handle onto the whole `WlEglSurface`
+    /// (and thus the ability to resize it directly, read back its size, or
+    /// destroy it) — just a narrow, cheap-to-clone channel for queuing
+    /// geometry. A burst of `ResizeHandle::resize` calls between two renders
+    /// coalesces into a single `wl_egl_window_resize`, applied on the next
+    /// `get_size` or `WlEglContext::swap_buffers`.
+    pub fn resize_handle(&self) -> ResizeHandle {
+        ResizeHandle { pending: self.pending_resize.clone() }
+    }
+
+    fn apply_pending_resize(&self) {
+        if let Some(pending) = self.pending_resize.lock().unwrap().take() {
+            self.resize(pending.width, pending.height, pending.dx, pending.dy);
+        }
+    }
+
     /// Raw pointer to the EGL surface
     ///
     /// You'll need this pointer to initialize the EGL context in your
@@ -99,6 +202,13 @@ impl WlEglSurface {
 
 impl Drop for WlEglSurface {
     fn drop(&mut self) {
+        // `try_destroy` / `destroy` already ran `wl_egl_window_destroy` and
+        // `mem::forget`'d `self`, so reaching here means the surface went out
+        // of scope normally: make sure no dangling `EGLSurface` is left behind.
+        assert_eq!(
+            self.live_egl_surfaces.load(Ordering::Acquire), 0,
+            "WlEglSurface dropped while an EGLSurface created from it is still alive"
+        );
         unsafe { ffi_dispatch!(WAYLAND_EGL_HANDLE, wl_egl_window_destroy, self.ptr); }
     }
 }
@@ -109,3 +219,389 @@ impl Deref for WlEglSurface {
         &self.surface
     }
 }
+
+/// An error occurring while bringing up or driving an EGL context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EglError {
+    /// `libEGL.so` could not be loaded, see `is_egl_available`
+    NotAvailable,
+    /// No `EGLDisplay` could be obtained from the wayland display
+    NoDisplay,
+    /// `eglInitialize` failed
+    InitializationFailed,
+    /// `eglChooseConfig` returned no matching config for the requested attributes
+    NoMatchingConfig,
+    /// `eglCreateContext` failed
+    ContextCreationFailed,
+    /// `eglCreate[Platform]WindowSurface` failed
+    SurfaceCreationFailed,
+    /// An EGL call that is expected to always succeed once the context is
+    /// up (`eglMakeCurrent`, `eglSwapBuffers`, `eglSwapInterval`) failed
+    CallFailed,
+    /// Tried to destroy a `WlEglSurface` while an `EGLSurface` created from
+    /// it is still alive
+    SurfaceStillInUse,
+}
+
+impl fmt::Display for EglError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            EglError::NotAvailable => "libEGL.so could not be loaded",
+            EglError::NoDisplay => "could not obtain an EGLDisplay",
+            EglError::InitializationFailed => "eglInitialize failed",
+            EglError::NoMatchingConfig => "no EGLConfig matches the requested attributes",
+            EglError::ContextCreationFailed => "eglCreateContext failed",
+            EglError::SurfaceCreationFailed => "eglCreate[Platform]WindowSurface failed",
+            EglError::CallFailed => "an EGL call failed",
+            EglError::SurfaceStillInUse => "WlEglSurface is still in use by a live EGLSurface",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for EglError {
+    fn description(&self) -> &str {
+        "an EGL error occurred"
+    }
+}
+
+/// Checks whether `ext_list` (a space-separated EGL extension string as
+/// returned by `eglQueryString`) contains `name`
+fn has_extension(ext_list: *const c_char, name: &str) -> bool {
+    if ext_list.is_null() {
+        return false;
+    }
+    let list = unsafe { CStr::from_ptr(ext_list) }.to_string_lossy();
+    list.split_whitespace().any(|e| e == name)
+}
+
+unsafe fn get_proc_address(name: &[u8]) -> *mut c_void {
+    ffi_dispatch!(EGL_HANDLE, eglGetProcAddress, name.as_ptr() as *const c_char)
+}
+
+/// A high-level EGL display and context, built on top of the Wayland
+/// platform extension
+///
+/// This wraps the usual `eglGetPlatformDisplayEXT`/`eglInitialize`/
+/// `eglChooseConfig`/`eglCreateContext` dance that every OpenGL client
+/// otherwise has to reimplement by hand. When `EGL_EXT_platform_wayland`
+/// is not advertised by the EGL implementation, it transparently falls
+/// back to plain `eglGetDisplay`.
+pub struct WlEglContext {
+    egl_display: EGLDisplay,
+    egl_config: EGLConfig,
+    egl_context: EGLContext,
+    create_platform_window_surface: Option<PFNEGLCREATEPLATFORMWINDOWSURFACEEXTPROC>,
+}
+
+unsafe impl Send for WlEglContext {}
+unsafe impl Sync for WlEglContext {}
+
+impl WlEglContext {
+    /// Create a new EGL context on the given wayland display
+    ///
+    /// `config_attribs` is passed to `eglChooseConfig` (an `EGL_NONE`
+    /// terminator is appended automatically), and `context_attribs` is
+    /// passed to `eglCreateContext` in the same way (use this to select
+    /// e.g. `EGL_CONTEXT_CLIENT_VERSION`).
+    pub fn new(
+        display: &WlDisplay,
+        config_attribs: &[EGLint],
+        context_attribs: &[EGLint],
+    ) -> Result<WlEglContext, EglError> {
+        WlEglContext::new_impl(display, Some((config_attribs, context_attribs)))
+    }
+
+    /// Create a config-less, surfaceless EGL context
+    ///
+    /// This is useful for clients that only ever render to EGLImages or
+    /// otherwise never need to create an on-screen `EGLSurface`, following
+    /// the same pattern as smithay's surfaceless contexts.
+    pub fn new_surfaceless(display: &WlDisplay, context_attribs: &[EGLint]) -> Result<WlEglContext, EglError> {
+        WlEglContext::new_impl(display, None).and_then(|mut ctx| {
+            let mut attribs = context_attribs.to_vec();
+            attribs.push(EGL_NONE);
+            let context = unsafe {
+                ffi_dispatch!(
+                    EGL_HANDLE,
+                    eglCreateContext,
+                    ctx.egl_display,
+                    ptr::null_mut(),
+                    EGL_NO_CONTEXT,
+                    attribs.as_ptr()
+                )
+            };
+            if context == EGL_NO_CONTEXT {
+                return Err(EglError::ContextCreationFailed);
+            }
+            ctx.egl_context = context;
+            Ok(ctx)
+        })
+    }
+
+    fn new_impl(
+        display: &WlDisplay,
+        config_and_context_attribs: Option<(&[EGLint], &[EGLint])>,
+    ) -> Result<WlEglContext, EglError> {
+        if !is_egl_available() {
+            return Err(EglError::NotAvailable);
+        }
+
+        let egl_display = unsafe {
+            let client_extensions = ffi_dispatch!(EGL_HANDLE, eglQueryString, EGL_NO_DISPLAY, EGL_EXTENSIONS);
+            let get_platform_display_ext = if has_extension(client_extensions, "EGL_EXT_platform_wayland") {
+                let proc = get_proc_address(b"eglGetPlatformDisplayEXT\0");
+                if proc.is_null() {
+                    None
+                } else {
+                    Some(mem::transmute::<_, PFNEGLGETPLATFORMDISPLAYEXTPROC>(proc))
+                }
+            } else {
+                None
+            };
+
+            match get_platform_display_ext {
+                Some(get_platform_display) => {
+                    get_platform_display(EGL_PLATFORM_WAYLAND_EXT, display.ptr() as *mut c_void, ptr::null())
+                }
+                None => ffi_dispatch!(EGL_HANDLE, eglGetDisplay, display.ptr() as *mut c_void),
+            }
+        };
+
+        if egl_display == EGL_NO_DISPLAY {
+            return Err(EglError::NoDisplay);
+        }
+
+        let mut major = 0;
+        let mut minor = 0;
+        if unsafe { ffi_dispatch!(EGL_HANDLE, eglInitialize, egl_display, &mut major, &mut minor) } == EGL_FALSE {
+            return Err(EglError::InitializationFailed);
+        }
+
+        let mut egl_config = ptr::null_mut();
+        if let Some((config_attribs, _)) = config_and_context_attribs {
+            let mut attribs = config_attribs.to_vec();
+            attribs.push(EGL_NONE);
+            let mut num_configs = 0;
+            let ok = unsafe {
+                ffi_dispatch!(
+                    EGL_HANDLE,
+                    eglChooseConfig,
+                    egl_display,
+                    attribs.as_ptr(),
+                    &mut egl_config,
+                    1,
+                    &mut num_configs
+                )
+            };
+            if ok == EGL_FALSE || num_configs == 0 {
+                unsafe { ffi_dispatch!(EGL_HANDLE, eglTerminate, egl_display); }
+                return Err(EglError::NoMatchingConfig);
+            }
+        }
+
+        let egl_context = match config_and_context_attribs {
+            Some((_, context_attribs)) => {
+                let mut attribs = context_attribs.to_vec();
+                attribs.push(EGL_NONE);
+                let context = unsafe {
+                    ffi_dispatch!(
+                        EGL_HANDLE,
+                        eglCreateContext,
+                        egl_display,
+                        egl_config,
+                        EGL_NO_CONTEXT,
+                        attribs.as_ptr()
+                    )
+                };
+                if context == EGL_NO_CONTEXT {
+                    unsafe { ffi_dispatch!(EGL_HANDLE, eglTerminate, egl_display); }
+                    return Err(EglError::ContextCreationFailed);
+                }
+                context
+            }
+            // the surfaceless path creates the context itself, once it knows
+            // it won't need a config
+            None => EGL_NO_CONTEXT,
+        };
+
+        let create_platform_window_surface = unsafe {
+            let proc = get_proc_address(b"eglCreatePlatformWindowSurfaceEXT\0");
+            if proc.is_null() {
+                None
+            } else {
+                Some(mem::transmute::<_, PFNEGLCREATEPLATFORMWINDOWSURFACEEXTPROC>(proc))
+            }
+        };
+
+        Ok(WlEglContext {
+            egl_display: egl_display,
+            egl_config: egl_config,
+            egl_context: egl_context,
+            create_platform_window_surface: create_platform_window_surface,
+        })
+    }
+
+    /// Create an `EGLSurface` from a `WlEglSurface`
+    ///
+    /// Prefers `eglCreatePlatformWindowSurfaceEXT` when available, and
+    /// falls back to `eglCreateWindowSurface` otherwise.
+    ///
+    /// This takes `context` wrapped in an `Rc` (rather than `&self`) because
+    /// the returned `EglWindowSurface` keeps both `context` and `surface`
+    /// alive for as long as the `EGLSurface` exists: it needs `context` to
+    /// stay alive so that `eglDestroySurface` isn't run against an
+    /// `EGLDisplay` that a dropped `WlEglContext` has already terminated,
+    /// and it marks `surface` as in-use so the underlying `wl_egl_window`
+    /// cannot be destroyed out from under EGL either, see
+    /// `WlEglSurface::try_destroy`.
+    pub fn create_window_surface(
+        context: &Rc<WlEglContext>,
+        surface: &Rc<WlEglSurface>,
+    ) -> Result<EglWindowSurface, EglError> {
+        let window = surface.egl_surface_ptr() as *mut c_void;
+        let egl_surface = match context.create_platform_window_surface {
+            Some(create_platform) => unsafe {
+                create_platform(context.egl_display, context.egl_config, window, ptr::null())
+            },
+            None => unsafe {
+                ffi_dispatch!(EGL_HANDLE, eglCreateWindowSurface, context.egl_display, context.egl_config, window, ptr::null())
+            },
+        };
+        if egl_surface == EGL_NO_SURFACE {
+            return Err(EglError::SurfaceCreationFailed);
+        }
+        surface.inc_live_surfaces();
+        Ok(EglWindowSurface {
+            context: context.clone(),
+            egl_surface: egl_surface,
+            surface: surface.clone(),
+        })
+    }
+
+    /// Make this context (and optionally a surface) current on this thread
+    pub fn make_current(&self, surface: EGLSurface) -> Result<(), EglError> {
+        let ret = unsafe {
+            ffi_dispatch!(EGL_HANDLE, eglMakeCurrent, self.egl_display, surface, surface, self.egl_context)
+        };
+        if ret == EGL_FALSE {
+            return Err(EglError::CallFailed);
+        }
+        Ok(())
+    }
+
+    /// Present the back buffer of `surface`
+    ///
+    /// Applies any resize queued through `surface`'s `ResizeHandle` before
+    /// swapping, so it lands before the next buffer is attached.
+    pub fn swap_buffers(&self, surface: &EglWindowSurface) -> Result<(), EglError> {
+        surface.surface.apply_pending_resize();
+        let ret = unsafe {
+            ffi_dispatch!(EGL_HANDLE, eglSwapBuffers, self.egl_display, surface.egl_surface)
+        };
+        if ret == EGL_FALSE {
+            return Err(EglError::CallFailed);
+        }
+        Ok(())
+    }
+
+    /// Set the minimum number of frames between buffer swaps, see `eglSwapInterval`
+    pub fn swap_interval(&self, interval: EGLint) -> Result<(), EglError> {
+        let ret = unsafe { ffi_dispatch!(EGL_HANDLE, eglSwapInterval, self.egl_display, interval) };
+        if ret == EGL_FALSE {
+            return Err(EglError::CallFailed);
+        }
+        Ok(())
+    }
+
+    /// Raw pointer to the underlying `EGLDisplay`
+    pub fn egl_display_ptr(&self) -> EGLDisplay {
+        self.egl_display
+    }
+
+    /// Raw pointer to the underlying `EGLContext`
+    pub fn egl_context_ptr(&self) -> EGLContext {
+        self.egl_context
+    }
+}
+
+impl Drop for WlEglContext {
+    fn drop(&mut self) {
+        unsafe {
+            if self.egl_context != EGL_NO_CONTEXT {
+                ffi_dispatch!(EGL_HANDLE, eglDestroyContext, self.egl_display, self.egl_context);
+            }
+            ffi_dispatch!(EGL_HANDLE, eglTerminate, self.egl_display);
+        }
+    }
+}
+
+/// An `EGLSurface` created from a `WlEglSurface`
+///
+/// This guard owns the `EGLSurface` and holds a clone of both `Rc`s that
+/// were passed to `WlEglContext::create_window_surface`: the `WlEglContext`
+/// clone keeps the `EGLDisplay` from being terminated (and the `EGLContext`
+/// destroyed) while this `EGLSurface` still references it, and the
+/// `WlEglSurface` clone keeps it marked as in-use for as long as this (or
+/// any sibling `EglWindowSurface` created from the same `WlEglSurface`) is
+/// alive. Together these are what prevent either the `EGLDisplay` or the
+/// `wl_egl_window` from being torn down while Mesa still considers this
+/// `EGLSurface` current, see `WlEglSurface::try_destroy`.
+pub struct EglWindowSurface {
+    context: Rc<WlEglContext>,
+    egl_surface: EGLSurface,
+    surface: Rc<WlEglSurface>,
+}
+
+impl EglWindowSurface {
+    /// Raw pointer to the underlying `EGLSurface`
+    pub fn egl_surface_ptr(&self) -> EGLSurface {
+        self.egl_surface
+    }
+
+    /// The `WlEglSurface` this `EGLSurface` was created from
+    pub fn surface(&self) -> &Rc<WlEglSurface> {
+        &self.surface
+    }
+}
+
+impl Drop for EglWindowSurface {
+    fn drop(&mut self) {
+        unsafe { ffi_dispatch!(EGL_HANDLE, eglDestroySurface, self.context.egl_display, self.egl_surface); }
+        self.surface.dec_live_surfaces();
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PendingResize {
+    width: i32,
+    height: i32,
+    dx: i32,
+    dy: i32,
+}
+
+/// A cheap, `Clone + Send` handle to queue a resize of a `WlEglSurface`
+///
+/// See `WlEglSurface::resize_handle`.
+#[derive(Clone)]
+pub struct ResizeHandle {
+    pending: Arc<Mutex<Option<PendingResize>>>,
+}
+
+impl ResizeHandle {
+    /// Queue a resize, overwriting any not yet applied
+    ///
+    /// Arguments are the same as `WlEglSurface::resize`. This does not call
+    /// `wl_egl_window_resize` itself: the resize is applied on the next
+    /// `WlEglSurface::get_size` or `WlEglContext::swap_buffers`, so several
+    /// calls made in quick succession (e.g. from a burst of compositor
+    /// `configure` events) only result in a single actual resize.
+    pub fn resize(&self, width: i32, height: i32, dx: i32, dy: i32) {
+        *self.pending.lock().unwrap() = Some(PendingResize {
+            width: width,
+            height: height,
+            dx: dx,
+            dy: dy,
+        });
+    }
+}
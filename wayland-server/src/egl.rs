@@ -0,0 +1,113 @@
+//! Server-side EGL utilities
+//!
+//! This module wraps the `EGL_WL_bind_wayland_display` vendor extension,
+//! which lets a compositor import client `wl_buffer`s as `EGLImage`s (and
+//! thus GL textures) directly, instead of copying pixel data through
+//! shared memory. It mirrors what `wlr_egl` (wlroots) and smithay expose.
+
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+
+use wayland_sys::egl::*;
+use wayland_sys::server::wl_resource;
+use Display;
+
+/// Checks if `libEGL.so` is available and can be used
+pub fn is_available() -> bool {
+    is_egl_available()
+}
+
+/// Checks whether `ext_list` (a space-separated EGL extension string as
+/// returned by `eglQueryString`) contains `name`
+fn has_extension(ext_list: *const c_char, name: &str) -> bool {
+    if ext_list.is_null() {
+        return false;
+    }
+    let list = unsafe { CStr::from_ptr(ext_list) }.to_string_lossy();
+    list.split_whitespace().any(|e| e == name)
+}
+
+/// Checks whether `egl_display` advertises `EGL_WL_bind_wayland_display`
+///
+/// `eglGetProcAddress` is allowed by the EGL spec to return a non-null
+/// pointer for an extension the display doesn't actually support, so the
+/// extension string must be checked before calling into any of the
+/// `*WaylandDisplayWL`/`*WaylandBufferWL` entry points.
+fn has_bind_wayland_display(egl_display: EGLDisplay) -> bool {
+    let extensions = unsafe { ffi_dispatch!(EGL_HANDLE, eglQueryString, egl_display, EGL_EXTENSIONS) };
+    has_extension(extensions, "EGL_WL_bind_wayland_display")
+}
+
+unsafe fn get_proc_address(name: &[u8]) -> *mut c_void {
+    ffi_dispatch!(EGL_HANDLE, eglGetProcAddress, name.as_ptr() as *const _)
+}
+
+/// Bind an `EGLDisplay` to a compositor's `wl_display`
+///
+/// Once bound, this process (acting as an EGL client on its own compositor)
+/// can create `EGLImage`s directly from the `wl_buffer`s attached by wayland
+/// clients. Call `unbind_display` with the same arguments before tearing
+/// either display down.
+///
+/// Returns `false` if the `EGL_WL_bind_wayland_display` extension is not
+/// advertised by this EGL implementation, or if the bind call itself failed.
+pub fn bind_display(egl_display: EGLDisplay, display: &Display) -> bool {
+    if !has_bind_wayland_display(egl_display) {
+        return false;
+    }
+    unsafe {
+        let proc = get_proc_address(b"eglBindWaylandDisplayWL\0");
+        if proc.is_null() {
+            return false;
+        }
+        let bind: PFNEGLBINDWAYLANDDISPLAYWL = mem::transmute(proc);
+        bind(egl_display, display.ptr() as *mut c_void) == EGL_TRUE
+    }
+}
+
+/// Undo a previous `bind_display`
+///
+/// This must be called before destroying either the `EGLDisplay` or the
+/// `Display`, while they are still both bound to each other.
+pub fn unbind_display(egl_display: EGLDisplay, display: &Display) -> bool {
+    if !has_bind_wayland_display(egl_display) {
+        return false;
+    }
+    unsafe {
+        let proc = get_proc_address(b"eglUnbindWaylandDisplayWL\0");
+        if proc.is_null() {
+            return false;
+        }
+        let unbind: PFNEGLUNBINDWAYLANDDISPLAYWL = mem::transmute(proc);
+        unbind(egl_display, display.ptr() as *mut c_void) == EGL_TRUE
+    }
+}
+
+/// Query an attribute of a client `wl_buffer`
+///
+/// `attribute` is one of `EGL_WIDTH`, `EGL_HEIGHT` or `EGL_TEXTURE_FORMAT`
+/// (which yields `EGL_TEXTURE_RGB` or `EGL_TEXTURE_RGBA`). `buffer` is the
+/// raw `wl_resource` of the client buffer, as seen in e.g. a `wl_surface`'s
+/// `attach` request.
+///
+/// Returns `None` if the extension is unavailable, `buffer` is not an
+/// EGL-backed buffer, or the query itself failed.
+pub fn query_buffer(egl_display: EGLDisplay, buffer: *mut wl_resource, attribute: EGLint) -> Option<i32> {
+    if !has_bind_wayland_display(egl_display) {
+        return None;
+    }
+    unsafe {
+        let proc = get_proc_address(b"eglQueryWaylandBufferWL\0");
+        if proc.is_null() {
+            return None;
+        }
+        let query: PFNEGLQUERYWAYLANDBUFFERWL = mem::transmute(proc);
+        let mut value = 0;
+        if query(egl_display, buffer as *mut c_void, attribute, &mut value) == EGL_TRUE {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
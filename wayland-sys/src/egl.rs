@@ -0,0 +1,135 @@
+//! Bindings to `libwayland-egl.so` and `libEGL.so`
+//!
+//! This module provides the raw FFI used to create EGL surfaces from
+//! wayland surfaces (via `libwayland-egl.so`), as well as the EGL entry
+//! points themselves (via `libEGL.so`) needed to bring up a full EGL
+//! display/context without every client having to hand-roll its own
+//! `dlopen`/`dlsym` dance.
+//!
+//! Both libraries are loaded lazily at runtime: use `is_lib_available()`
+//! and `is_egl_available()` to check whether they could be found before
+//! using the `ffi_dispatch!` handles below.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use std::os::raw::{c_char, c_int, c_void};
+
+use client::wl_proxy;
+
+pub enum wl_egl_window {}
+
+external_library!(WaylandEgl, "wayland-egl",
+    functions:
+        fn wl_egl_window_create(*mut wl_proxy, c_int, c_int) -> *mut wl_egl_window,
+        fn wl_egl_window_destroy(*mut wl_egl_window) -> (),
+        fn wl_egl_window_resize(*mut wl_egl_window, c_int, c_int, c_int, c_int) -> (),
+        fn wl_egl_window_get_attached_size(*mut wl_egl_window, *mut c_int, *mut c_int) -> (),
+);
+
+/// Checks if `libwayland-egl.so` is available and can be used
+pub fn is_lib_available() -> bool {
+    WAYLAND_EGL_OPTION.is_some()
+}
+
+lazy_static!(
+    pub static ref WAYLAND_EGL_OPTION: Option<WaylandEgl> = {
+        WaylandEgl::open("libwayland-egl.so")
+            .or_else(|_| WaylandEgl::open("libwayland-egl.so.1"))
+            .ok()
+    };
+    pub static ref WAYLAND_EGL_HANDLE: &'static WaylandEgl = {
+        WAYLAND_EGL_OPTION.as_ref().expect("Library libwayland-egl.so could not be loaded.")
+    };
+);
+
+// Opaque EGL handle types. EGL itself defines these as incomplete struct
+// pointers, we just need something with the right pointer semantics.
+pub enum EGLDisplayImpl {}
+pub enum EGLConfigImpl {}
+pub enum EGLContextImpl {}
+pub enum EGLSurfaceImpl {}
+
+pub type EGLDisplay = *mut EGLDisplayImpl;
+pub type EGLConfig = *mut EGLConfigImpl;
+pub type EGLContext = *mut EGLContextImpl;
+pub type EGLSurface = *mut EGLSurfaceImpl;
+
+pub type EGLint = i32;
+pub type EGLBoolean = c_int;
+pub type EGLenum = c_int;
+
+pub const EGL_FALSE: EGLBoolean = 0;
+pub const EGL_TRUE: EGLBoolean = 1;
+
+pub const EGL_NO_DISPLAY: EGLDisplay = 0 as EGLDisplay;
+pub const EGL_NO_CONTEXT: EGLContext = 0 as EGLContext;
+pub const EGL_NO_SURFACE: EGLSurface = 0 as EGLSurface;
+
+pub const EGL_NONE: EGLint = 0x3038;
+pub const EGL_EXTENSIONS: EGLint = 0x3055;
+
+pub const EGL_SURFACE_TYPE: EGLint = 0x3033;
+pub const EGL_WINDOW_BIT: EGLint = 0x0004;
+pub const EGL_RENDERABLE_TYPE: EGLint = 0x3040;
+pub const EGL_OPENGL_ES2_BIT: EGLint = 0x0004;
+pub const EGL_OPENGL_BIT: EGLint = 0x0008;
+pub const EGL_CONTEXT_CLIENT_VERSION: EGLint = 0x3098;
+
+/// `EGL_PLATFORM_WAYLAND_EXT`, from the `EGL_EXT_platform_wayland` extension
+pub const EGL_PLATFORM_WAYLAND_EXT: EGLenum = 0x31D8;
+
+pub type PFNEGLGETPLATFORMDISPLAYEXTPROC =
+    unsafe extern "C" fn(EGLenum, *mut c_void, *const EGLint) -> EGLDisplay;
+pub type PFNEGLCREATEPLATFORMWINDOWSURFACEEXTPROC =
+    unsafe extern "C" fn(EGLDisplay, EGLConfig, *mut c_void, *const EGLint) -> EGLSurface;
+
+// Constants and function pointer types for the `EGL_WL_bind_wayland_display`
+// vendor extension, used by compositors to import client `wl_buffer`s as
+// EGL images / GL textures.
+pub const EGL_WAYLAND_BUFFER_WL: EGLint = 0x31D5;
+pub const EGL_WAYLAND_PLANE_WL: EGLint = 0x31D6;
+pub const EGL_TEXTURE_FORMAT: EGLint = 0x3080;
+pub const EGL_WIDTH: EGLint = 0x3057;
+pub const EGL_HEIGHT: EGLint = 0x3056;
+pub const EGL_TEXTURE_RGB: EGLint = 0x305D;
+pub const EGL_TEXTURE_RGBA: EGLint = 0x305E;
+pub const EGL_WAYLAND_Y_INVERTED_WL: EGLint = 0x31DB;
+
+pub type PFNEGLBINDWAYLANDDISPLAYWL = unsafe extern "C" fn(EGLDisplay, *mut c_void) -> EGLBoolean;
+pub type PFNEGLUNBINDWAYLANDDISPLAYWL = unsafe extern "C" fn(EGLDisplay, *mut c_void) -> EGLBoolean;
+pub type PFNEGLQUERYWAYLANDBUFFERWL =
+    unsafe extern "C" fn(EGLDisplay, *mut c_void, EGLint, *mut EGLint) -> EGLBoolean;
+
+external_library!(Egl, "EGL",
+    functions:
+        fn eglGetDisplay(*mut c_void) -> EGLDisplay,
+        fn eglInitialize(EGLDisplay, *mut EGLint, *mut EGLint) -> EGLBoolean,
+        fn eglTerminate(EGLDisplay) -> EGLBoolean,
+        fn eglChooseConfig(EGLDisplay, *const EGLint, *mut EGLConfig, EGLint, *mut EGLint) -> EGLBoolean,
+        fn eglCreateContext(EGLDisplay, EGLConfig, EGLContext, *const EGLint) -> EGLContext,
+        fn eglDestroyContext(EGLDisplay, EGLContext) -> EGLBoolean,
+        fn eglCreateWindowSurface(EGLDisplay, EGLConfig, *mut c_void, *const EGLint) -> EGLSurface,
+        fn eglDestroySurface(EGLDisplay, EGLSurface) -> EGLBoolean,
+        fn eglMakeCurrent(EGLDisplay, EGLSurface, EGLSurface, EGLContext) -> EGLBoolean,
+        fn eglSwapBuffers(EGLDisplay, EGLSurface) -> EGLBoolean,
+        fn eglSwapInterval(EGLDisplay, EGLint) -> EGLBoolean,
+        fn eglQueryString(EGLDisplay, EGLint) -> *const c_char,
+        fn eglGetProcAddress(*const c_char) -> *mut c_void,
+        fn eglGetError() -> EGLint,
+);
+
+/// Checks if `libEGL.so` is available and can be used
+pub fn is_egl_available() -> bool {
+    EGL_OPTION.is_some()
+}
+
+lazy_static!(
+    pub static ref EGL_OPTION: Option<Egl> = {
+        Egl::open("libEGL.so")
+            .or_else(|_| Egl::open("libEGL.so.1"))
+            .ok()
+    };
+    pub static ref EGL_HANDLE: &'static Egl = {
+        EGL_OPTION.as_ref().expect("Library libEGL.so could not be loaded.")
+    };
+);